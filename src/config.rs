@@ -0,0 +1,175 @@
+use serde::Deserialize;
+use std::path::Path;
+
+fn default_listen_addr() -> String {
+    "0.0.0.0:8080".to_string()
+}
+
+fn default_poll_interval_secs() -> u64 {
+    300
+}
+
+fn default_session_path() -> String {
+    "session.dat".to_string()
+}
+
+fn default_tls_https_addr() -> String {
+    "0.0.0.0:8443".to_string()
+}
+
+fn default_tls_cert_path() -> String {
+    "cert.pem".to_string()
+}
+
+fn default_tls_account_key_path() -> String {
+    "acme_account_key.der".to_string()
+}
+
+fn default_acme_directory_url() -> String {
+    "https://acme-v02.api.letsencrypt.org/directory".to_string()
+}
+
+/// Settings for running headless (systemd, container, etc.) instead of
+/// relying on an interactive TTY. Loaded from an optional TOML file and
+/// then overridden by environment variables, so a deployment can mix a
+/// checked-in config with secrets injected at runtime.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub email: Option<String>,
+    pub password: Option<String>,
+    /// Where the plain-HTTP `/metrics` listener binds. Also answers ACME
+    /// HTTP-01 challenges, so if `tls_enabled` is set this must be on port
+    /// 80 - that's the port the ACME server connects to for validation.
+    pub listen_addr: String,
+    pub poll_interval_secs: u64,
+    pub session_path: String,
+    /// Serve `/metrics` over HTTPS using an ACME-issued certificate.
+    /// Requires `listen_addr` to be on port 80; see its doc comment.
+    pub tls_enabled: bool,
+    /// Domain the ACME certificate is issued for. Required if `tls_enabled`.
+    pub tls_domain: Option<String>,
+    /// Contact address submitted to the ACME account. Defaults to `email`.
+    pub tls_contact_email: Option<String>,
+    pub tls_https_addr: String,
+    pub tls_cert_path: String,
+    pub tls_account_key_path: String,
+    pub acme_directory_url: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            email: None,
+            password: None,
+            listen_addr: default_listen_addr(),
+            poll_interval_secs: default_poll_interval_secs(),
+            session_path: default_session_path(),
+            tls_enabled: false,
+            tls_domain: None,
+            tls_contact_email: None,
+            tls_https_addr: default_tls_https_addr(),
+            tls_cert_path: default_tls_cert_path(),
+            tls_account_key_path: default_tls_account_key_path(),
+            acme_directory_url: default_acme_directory_url(),
+        }
+    }
+}
+
+impl Config {
+    /// Read `path` as TOML if it exists, then apply environment variable
+    /// overrides (`WS_EMAIL`, `WS_PASSWORD`, `WS_LISTEN_ADDR`,
+    /// `WS_POLL_INTERVAL_SECS`, `WS_SESSION_PATH`, `WS_TLS_ENABLED`,
+    /// `WS_TLS_DOMAIN`, `WS_TLS_CONTACT_EMAIL`, `WS_TLS_HTTPS_ADDR`,
+    /// `WS_TLS_CERT_PATH`, `WS_TLS_ACCOUNT_KEY_PATH`, `WS_ACME_DIRECTORY_URL`)
+    /// on top.
+    pub fn load(path: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+        let mut config = if path.exists() {
+            let contents = std::fs::read_to_string(path)?;
+            toml::from_str(&contents)?
+        } else {
+            Config::default()
+        };
+
+        if let Ok(email) = std::env::var("WS_EMAIL") {
+            config.email = Some(email);
+        }
+        if let Ok(password) = std::env::var("WS_PASSWORD") {
+            config.password = Some(password);
+        }
+        if let Ok(listen_addr) = std::env::var("WS_LISTEN_ADDR") {
+            config.listen_addr = listen_addr;
+        }
+        if let Ok(poll_interval_secs) = std::env::var("WS_POLL_INTERVAL_SECS") {
+            config.poll_interval_secs = poll_interval_secs.parse()?;
+        }
+        if let Ok(session_path) = std::env::var("WS_SESSION_PATH") {
+            config.session_path = session_path;
+        }
+        if let Ok(tls_enabled) = std::env::var("WS_TLS_ENABLED") {
+            config.tls_enabled = tls_enabled.parse()?;
+        }
+        if let Ok(tls_domain) = std::env::var("WS_TLS_DOMAIN") {
+            config.tls_domain = Some(tls_domain);
+        }
+        if let Ok(tls_contact_email) = std::env::var("WS_TLS_CONTACT_EMAIL") {
+            config.tls_contact_email = Some(tls_contact_email);
+        }
+        if let Ok(tls_https_addr) = std::env::var("WS_TLS_HTTPS_ADDR") {
+            config.tls_https_addr = tls_https_addr;
+        }
+        if let Ok(tls_cert_path) = std::env::var("WS_TLS_CERT_PATH") {
+            config.tls_cert_path = tls_cert_path;
+        }
+        if let Ok(tls_account_key_path) = std::env::var("WS_TLS_ACCOUNT_KEY_PATH") {
+            config.tls_account_key_path = tls_account_key_path;
+        }
+        if let Ok(acme_directory_url) = std::env::var("WS_ACME_DIRECTORY_URL") {
+            config.acme_directory_url = acme_directory_url;
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Config::load reads process-wide environment variables, so serialize
+    // tests that touch them to avoid cross-test interference.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn load_applies_env_overrides_on_top_of_the_toml_file() {
+        let _guard = ENV_LOCK.lock().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("ws-config-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("config.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+            email = "file@example.com"
+            listen_addr = "0.0.0.0:9090"
+            "#,
+        )
+        .unwrap();
+
+        std::env::set_var("WS_EMAIL", "env@example.com");
+        std::env::set_var("WS_POLL_INTERVAL_SECS", "60");
+
+        let config = Config::load(&config_path).unwrap();
+
+        std::env::remove_var("WS_EMAIL");
+        std::env::remove_var("WS_POLL_INTERVAL_SECS");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Env var wins over the file...
+        assert_eq!(config.email, Some("env@example.com".to_string()));
+        assert_eq!(config.poll_interval_secs, 60);
+        // ...but values the file sets and the environment doesn't are kept.
+        assert_eq!(config.listen_addr, "0.0.0.0:9090");
+    }
+}
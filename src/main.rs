@@ -1,11 +1,26 @@
 use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
-use prometheus::{self, register_gauge_vec, Encoder, GaugeVec, TextEncoder};
+use prometheus::{
+    self, register_counter, register_counter_vec, register_gauge, register_gauge_vec, Counter,
+    CounterVec, Encoder, Gauge, GaugeVec, TextEncoder,
+};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::str::FromStr;
-use tiny_http::{Response, Server};
+use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use tiny_http::{Request, Response, Server};
 
+mod acme;
+mod config;
+mod positions;
+mod session;
+
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+#[macro_export]
 macro_rules! api {
     ($path:expr) => {
         format!("https://api.production.wealthsimple.com/v1/{}", $path)
@@ -36,6 +51,28 @@ lazy_static! {
         "sum of all positions in the account",
         &["account_id", "account_type", "account_name"]
     ).unwrap();
+    static ref DEPOSITS_TOTAL: CounterVec = register_counter_vec!(
+        "wealthsimple_deposits_total",
+        "monotonic counter tracking increases in the total amount deposited",
+        &["account_id", "account_type", "account_name"]
+    ).unwrap();
+    static ref WITHDRAWALS_TOTAL: CounterVec = register_counter_vec!(
+        "wealthsimple_withdrawals_total",
+        "monotonic counter tracking increases in the total amount withdrawn",
+        &["account_id", "account_type", "account_name"]
+    ).unwrap();
+    static ref LAST_SCRAPE_SUCCESS_TIMESTAMP: Gauge = register_gauge!(
+        "wealthsimple_last_scrape_success_timestamp",
+        "unix timestamp of the last successful poll of the Wealthsimple API"
+    ).unwrap();
+    static ref SCRAPE_ERRORS_TOTAL: Counter = register_counter!(
+        "wealthsimple_scrape_errors_total",
+        "count of failed fetches or unparseable responses from the Wealthsimple API"
+    ).unwrap();
+    static ref TOKEN_REFRESHES_TOTAL: Counter = register_counter!(
+        "wealthsimple_token_refreshes_total",
+        "count of re-logins attempted to refresh the access token after a 401"
+    ).unwrap();
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -69,8 +106,8 @@ struct Owner<'a> {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct Amount<'a> {
-    amount: &'a str,
+pub(crate) struct Amount {
+    pub(crate) amount: Decimal,
     currency: Currency,
 }
 
@@ -84,11 +121,11 @@ struct Account<'a> {
     base_currency: Currency,
     status: Status,
     owners: Vec<Owner<'a>>,
-    net_liquidation: Amount<'a>,
-    gross_position: Amount<'a>,
-    total_deposits: Amount<'a>,
-    total_withdrawals: Amount<'a>,
-    withdrawn_earnings: Amount<'a>,
+    net_liquidation: Amount,
+    gross_position: Amount,
+    total_deposits: Amount,
+    total_withdrawals: Amount,
+    withdrawn_earnings: Amount,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
 }
@@ -102,84 +139,396 @@ struct AccountsResponse<'a> {
     results: Vec<Account<'a>>,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let username = rprompt::prompt_reply_stdout("Email: ")?;
-    let password = rpassword::prompt_password_stdout("Password: ")?;
-    let id = uuid::Uuid::new_v4().to_simple().to_string();
-    let mut otp_claim = None;
-
-    let mut token = login(&id, &username, &password, &mut otp_claim)?;
+/// Convert a `Decimal` amount to the `f64` a `GaugeVec` expects. Amounts are
+/// only converted to floating point when handed to a metric (here, and for
+/// counter deltas in `inc_counter_from_cumulative`), so loss of precision is
+/// confined to the final metric value rather than any arithmetic on it.
+pub(crate) fn set_amount(gauge: &GaugeVec, label_values: &[&str], amount: Decimal) {
+    match amount.to_f64() {
+        Some(value) => gauge.with_label_values(label_values).set(value),
+        None => eprintln!("amount {} doesn't fit in an f64, skipping", amount),
+    }
+}
 
-    std::thread::spawn(|| {
-        let server = Server::http("0.0.0.0:8080").unwrap();
+/// `total_deposits`/`total_withdrawals` are cumulative lifetime totals from
+/// the API, not something we accumulate ourselves, so track the last value
+/// seen per account and turn each increase into a counter bump. This is
+/// what lets `rate()`/`increase()` work in Prometheus, which a gauge alone
+/// can't give you.
+fn inc_counter_from_cumulative(
+    counter: &CounterVec,
+    label_values: &[&str],
+    last_totals: &mut HashMap<String, Decimal>,
+    account_id: &str,
+    new_total: Decimal,
+) {
+    let delta = match last_totals.get(account_id) {
+        Some(&last) if new_total >= last => new_total - last,
+        Some(_) => {
+            eprintln!(
+                "cumulative total for account {} went backwards, resetting counter baseline",
+                account_id
+            );
+            Decimal::ZERO
+        }
+        None => Decimal::ZERO,
+    };
 
-        for request in server.incoming_requests() {
-            if request.url() != "/metrics" {
-                if let Err(e) = request.respond(Response::empty(404)) {
-                    eprintln!("Failed to respond to request: {}", e);
-                }
-                continue;
-            }
+    if let Some(delta) = delta.to_f64() {
+        if delta > 0.0 {
+            counter.with_label_values(label_values).inc_by(delta);
+        }
+    }
 
-            let mut buffer = Vec::new();
-            let encoder = TextEncoder::new();
+    last_totals.insert(account_id.to_string(), new_total);
+}
 
-            let metrics = prometheus::gather();
-            if let Err(e) = encoder.encode(&metrics, &mut buffer) {
-                eprintln!("Failed to encode metrics data: {}", e);
-                continue;
-            }
+/// Read `email` from config, falling back to an interactive prompt if a TTY
+/// is attached. Returns an error in a headless context with nothing
+/// configured, rather than blocking on stdin forever.
+fn resolve_email(config: &config::Config) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(email) = &config.email {
+        return Ok(email.clone());
+    }
+    if std::io::stdin().is_terminal() {
+        return Ok(rprompt::prompt_reply_stdout("Email: ")?);
+    }
+    Err("no email configured and no TTY to prompt on".into())
+}
 
-            let output = String::from_utf8(buffer).unwrap();
-            if let Err(e) = request.respond(Response::from_string(output)) {
-                eprintln!("Failed to send metrics data: {}", e);
-            }
-        }
-    });
+/// Same as `resolve_email`, but for the account password.
+fn resolve_password(config: &config::Config) -> Result<Secret<String>, Box<dyn std::error::Error>> {
+    if let Some(password) = &config.password {
+        return Ok(Secret::new(password.clone()));
+    }
+    if std::io::stdin().is_terminal() {
+        return Ok(Secret::new(rpassword::prompt_password_stdout("Password: ")?));
+    }
+    Err("no password configured and no TTY to prompt on".into())
+}
 
+/// GET `url` with the current bearer token, transparently logging back in
+/// and retrying once if the token has expired. Used for every authenticated
+/// endpoint so the 401-refresh behaviour only lives in one place.
+#[allow(clippy::too_many_arguments)]
+fn authenticated_get(
+    url: &str,
+    id: &str,
+    username: &str,
+    password: &Secret<String>,
+    token: &mut Secret<String>,
+    otp_claim: &mut Option<String>,
+    session_path: &Path,
+) -> Result<minreq::Response, Box<dyn std::error::Error>> {
+    let mut refreshed = false;
     loop {
-        let resp = minreq::get(api!("accounts"))
-            .with_header("Authorization", &token)
+        let resp = minreq::get(url)
+            .with_header("Authorization", token.expose_secret())
             .with_header("Accept", "*/*")
             .with_header("User-Agent", "curl/7.64.1")
             .send()?;
 
-        if resp.status_code == 401 {
+        if resp.status_code == 401 && !refreshed {
             println!(
                 "got 401, need to log in again: {}",
                 std::str::from_utf8(resp.as_bytes())?
             );
-            token = login(&id, &username, &password, &mut otp_claim)?;
+            TOKEN_REFRESHES_TOTAL.inc();
+            *token = login(id, username, password.expose_secret(), otp_claim)?;
+            save_session(session_path, password, id, token, otp_claim);
+            refreshed = true;
             continue;
         } else if resp.status_code != 200 {
+            // Leave counting this as a scrape error to the caller: a process
+            // that's dead from `?` propagating this `Err` can't export the
+            // counter it just incremented.
             return Err(
                 format!("Request failed: {}", std::str::from_utf8(resp.as_bytes())?).into(),
             );
         }
 
-        let accounts: AccountsResponse = resp.json()?;
+        return Ok(resp);
+    }
+}
 
-        for account in accounts.results {
-            let label_values = &[account.id, account.type_, account.nickname.unwrap_or("")];
+/// Serve ACME HTTP-01 challenges and, if `serve_metrics` is set, `/metrics`.
+/// Shared by the plain HTTP and ACME-provisioned HTTPS listeners so both
+/// answer challenges the same way; `serve_metrics` is false on the plain
+/// HTTP listener whenever TLS is enabled, so account balances are never
+/// readable in cleartext.
+fn handle_request(request: Request, challenges: &acme::ChallengeStore, serve_metrics: bool) {
+    if let Some(token) = request.url().strip_prefix("/.well-known/acme-challenge/") {
+        let key_authorization = challenges.lock().unwrap().get(token).cloned();
+        let response = match key_authorization {
+            Some(key_authorization) => request.respond(Response::from_string(key_authorization)),
+            None => request.respond(Response::empty(404)),
+        };
+        if let Err(e) = response {
+            eprintln!("Failed to respond to ACME challenge request: {}", e);
+        }
+        return;
+    }
+
+    if !serve_metrics || request.url() != "/metrics" {
+        if let Err(e) = request.respond(Response::empty(404)) {
+            eprintln!("Failed to respond to request: {}", e);
+        }
+        return;
+    }
+
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+
+    let metrics = prometheus::gather();
+    if let Err(e) = encoder.encode(&metrics, &mut buffer) {
+        eprintln!("Failed to encode metrics data: {}", e);
+        return;
+    }
+
+    let output = String::from_utf8(buffer).unwrap();
+    if let Err(e) = request.respond(Response::from_string(output)) {
+        eprintln!("Failed to send metrics data: {}", e);
+    }
+}
+
+/// Matches the `fn(Request, &ChallengeStore)` pointer `run_https_server`
+/// expects: the HTTPS listener only ever serves over TLS with the ACME
+/// certificate, so it's always safe to answer `/metrics` there.
+fn handle_https_request(request: Request, challenges: &acme::ChallengeStore) {
+    handle_request(request, challenges, true);
+}
 
-            if let Ok(amount) = f64::from_str(account.total_deposits.amount) {
-                DEPOSITED.with_label_values(label_values).set(amount);
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config_path = std::env::var("WS_CONFIG_PATH").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string());
+    let config = config::Config::load(Path::new(&config_path))?;
+
+    let session_path = Path::new(&config.session_path);
+    let saved_session = if session_path.exists() {
+        let password = resolve_password(&config)?;
+        match session::load(session_path, &password) {
+            Ok(session) => Some((session, password)),
+            Err(e) => {
+                eprintln!("Failed to load saved session, logging in again: {}", e);
+                None
             }
+        }
+    } else {
+        None
+    };
 
-            if let Ok(amount) = f64::from_str(account.total_withdrawals.amount) {
-                WITHDRAWN.with_label_values(label_values).set(amount);
+    let (id, mut otp_claim, mut token, username, password) = match saved_session {
+        Some((session, password)) => (
+            session.device_id,
+            session.otp_claim,
+            Secret::new(format!("Bearer {}", session.access_token)),
+            resolve_email(&config)?,
+            password,
+        ),
+        None => {
+            let username = resolve_email(&config)?;
+            let password = resolve_password(&config)?;
+            let id = uuid::Uuid::new_v4().to_simple().to_string();
+            let mut otp_claim = None;
+            let token = login(&id, &username, password.expose_secret(), &mut otp_claim)?;
+            save_session(session_path, &password, &id, &token, &otp_claim);
+            (id, otp_claim, token, username, password)
+        }
+    };
+
+    let challenges = acme::new_challenge_store();
+
+    let listen_addr = config.listen_addr.clone();
+    let http_challenges = challenges.clone();
+    let serve_metrics_over_http = !config.tls_enabled;
+    std::thread::spawn(move || {
+        let server = Server::http(&listen_addr).unwrap();
+
+        for request in server.incoming_requests() {
+            handle_request(request, &http_challenges, serve_metrics_over_http);
+        }
+    });
+
+    if config.tls_enabled {
+        // The ACME server validates HTTP-01 challenges by connecting to the
+        // domain on port 80, and `handle_request` only answers them on the
+        // `listen_addr` server spawned above - so that's the port it has to
+        // be on for validation to ever succeed.
+        if !config.listen_addr.ends_with(":80") {
+            return Err(format!(
+                "tls_enabled requires listen_addr to be on port 80 for ACME HTTP-01 validation, got {}",
+                config.listen_addr
+            )
+            .into());
+        }
+
+        let domain = config
+            .tls_domain
+            .clone()
+            .ok_or("tls_enabled is set but tls_domain is not configured")?;
+        let contact_email = config.tls_contact_email.clone().unwrap_or_else(|| username.clone());
+        let https_addr = config.tls_https_addr.clone();
+        let directory_url = config.acme_directory_url.clone();
+        let cert_path = PathBuf::from(&config.tls_cert_path);
+        let account_key_path = PathBuf::from(&config.tls_account_key_path);
+        let https_challenges = challenges.clone();
+
+        std::thread::spawn(move || {
+            acme::run_https_server(
+                https_addr,
+                domain,
+                contact_email,
+                directory_url,
+                cert_path,
+                account_key_path,
+                https_challenges,
+                handle_https_request,
+            );
+        });
+    }
+
+    let mut last_deposits = HashMap::new();
+    let mut last_withdrawals = HashMap::new();
+    let mut last_position_keys: HashMap<String, HashSet<(String, String)>> = HashMap::new();
+
+    loop {
+        let resp = match authenticated_get(
+            &api!("accounts"),
+            &id,
+            &username,
+            &password,
+            &mut token,
+            &mut otp_claim,
+            session_path,
+        ) {
+            Ok(resp) => resp,
+            Err(e) => {
+                eprintln!("Failed to fetch accounts: {}", e);
+                SCRAPE_ERRORS_TOTAL.inc();
+                std::thread::sleep(std::time::Duration::from_secs(config.poll_interval_secs));
+                continue;
+            }
+        };
+        let accounts: AccountsResponse = match resp.json() {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                eprintln!("Failed to parse accounts response: {}", e);
+                SCRAPE_ERRORS_TOTAL.inc();
+                std::thread::sleep(std::time::Duration::from_secs(config.poll_interval_secs));
+                continue;
+            }
+        };
+
+        let mut poll_had_errors = false;
+
+        for account in &accounts.results {
+            let label_values = &[account.id, account.type_, account.nickname.unwrap_or("")];
+
+            set_amount(&DEPOSITED, label_values, account.total_deposits.amount);
+            set_amount(&WITHDRAWN, label_values, account.total_withdrawals.amount);
+            set_amount(&NET_LIQUIDATION, label_values, account.net_liquidation.amount);
+            set_amount(&GROSS_POSITION, label_values, account.gross_position.amount);
+            inc_counter_from_cumulative(
+                &DEPOSITS_TOTAL,
+                label_values,
+                &mut last_deposits,
+                account.id,
+                account.total_deposits.amount,
+            );
+            inc_counter_from_cumulative(
+                &WITHDRAWALS_TOTAL,
+                label_values,
+                &mut last_withdrawals,
+                account.id,
+                account.total_withdrawals.amount,
+            );
+
+            // A closed account holds nothing, and Wealthsimple won't serve
+            // its positions anymore either - clear out whatever gauges are
+            // left from when it was still open and move on.
+            if matches!(account.status, Status::Closed) {
+                if let Some(stale_keys) = last_position_keys.remove(account.id) {
+                    for (symbol, name) in &stale_keys {
+                        positions::remove(account.id, symbol, name);
+                    }
+                }
+                continue;
             }
 
-            if let Ok(amount) = f64::from_str(account.net_liquidation.amount) {
-                NET_LIQUIDATION.with_label_values(label_values).set(amount);
+            let positions_resp = match authenticated_get(
+                &api!(format!("account/positions?account_id={}", account.id)),
+                &id,
+                &username,
+                &password,
+                &mut token,
+                &mut otp_claim,
+                session_path,
+            ) {
+                Ok(resp) => resp,
+                Err(e) => {
+                    eprintln!("Failed to fetch positions for account {}: {}", account.id, e);
+                    SCRAPE_ERRORS_TOTAL.inc();
+                    poll_had_errors = true;
+                    continue;
+                }
+            };
+            let positions: positions::PositionsResponse = match positions_resp.json() {
+                Ok(positions) => positions,
+                Err(e) => {
+                    eprintln!(
+                        "Failed to parse positions response for account {}: {}",
+                        account.id, e
+                    );
+                    SCRAPE_ERRORS_TOTAL.inc();
+                    poll_had_errors = true;
+                    continue;
+                }
+            };
+
+            let mut current_keys = HashSet::new();
+            for position in &positions.results {
+                positions::record(position);
+                let (symbol, name) = position.key();
+                current_keys.insert((symbol.to_string(), name.to_string()));
             }
 
-            if let Ok(amount) = f64::from_str(account.gross_position.amount) {
-                GROSS_POSITION.with_label_values(label_values).set(amount);
+            if let Some(previous_keys) = last_position_keys.insert(account.id.to_string(), current_keys.clone()) {
+                for (symbol, name) in previous_keys.difference(&current_keys) {
+                    positions::remove(account.id, symbol, name);
+                }
             }
         }
 
-        std::thread::sleep(std::time::Duration::from_secs(300));
+        // Only a poll that pulled every account's positions cleanly counts
+        // as a "last successful scrape" - a poll with per-account errors
+        // already bumped SCRAPE_ERRORS_TOTAL and shouldn't also claim success.
+        if !poll_had_errors {
+            LAST_SCRAPE_SUCCESS_TIMESTAMP.set(Utc::now().timestamp() as f64);
+        }
+        std::thread::sleep(std::time::Duration::from_secs(config.poll_interval_secs));
+    }
+}
+
+/// Persist the current device id, access token, and OTP claim so the next
+/// run can skip the interactive login/2FA flow. Failures are logged but
+/// non-fatal since the process can keep running off the in-memory token.
+fn save_session(
+    path: &Path,
+    password: &Secret<String>,
+    id: &str,
+    token: &Secret<String>,
+    otp_claim: &Option<String>,
+) {
+    let session = session::Session {
+        device_id: id.to_string(),
+        access_token: token
+            .expose_secret()
+            .trim_start_matches("Bearer ")
+            .to_string(),
+        otp_claim: otp_claim.clone(),
+    };
+
+    if let Err(e) = session::save(path, password, &session) {
+        eprintln!("Failed to save session: {}", e);
     }
 }
 
@@ -197,7 +546,7 @@ fn login(
     username: &str,
     password: &str,
     otp_claim: &mut Option<String>,
-) -> Result<String, Box<dyn std::error::Error>> {
+) -> Result<Secret<String>, Box<dyn std::error::Error>> {
     let mut payload = HashMap::new();
 
     payload.insert("username", username);
@@ -226,6 +575,9 @@ fn login(
             .map(|s| s == "required; method=app")
             .unwrap_or(false) =>
         {
+            if !std::io::stdin().is_terminal() {
+                return Err("2FA is required but no TTY is attached to prompt for a code".into());
+            }
             let otp = rprompt::prompt_reply_stdout("2FA code: ")?;
             let resp = minreq::post(api!("oauth/token"))
                 .with_header("Accept", "application/json")
@@ -238,7 +590,7 @@ fn login(
             if resp.status_code == 200 {
                 otp_claim.replace(resp.headers["x-wealthsimple-otp-claim"].clone());
                 let body: LoginResponse = resp.json()?;
-                Ok(format!("Bearer {}", body.access_token))
+                Ok(Secret::new(format!("Bearer {}", body.access_token)))
             } else {
                 Err(format!(
                     "Failed to log in after 2fa: {}",
@@ -249,7 +601,7 @@ fn login(
         }
         200 => {
             let body: LoginResponse = resp.json()?;
-            Ok(format!("Bearer {}", body.access_token))
+            Ok(Secret::new(format!("Bearer {}", body.access_token)))
         }
         _ => Err(format!(
             "Failed to log in: {:#?} {}",
@@ -259,3 +611,49 @@ fn login(
         .into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Opts;
+    use rust_decimal_macros::dec;
+
+    fn counter() -> CounterVec {
+        CounterVec::new(Opts::new("test_counter", "test"), &["account_id"]).unwrap()
+    }
+
+    #[test]
+    fn inc_counter_from_cumulative_first_observation_sets_baseline_without_incrementing() {
+        let counter = counter();
+        let mut last_totals = HashMap::new();
+
+        inc_counter_from_cumulative(&counter, &["a1"], &mut last_totals, "a1", dec!(100));
+
+        assert_eq!(counter.with_label_values(&["a1"]).get(), 0.0);
+        assert_eq!(last_totals.get("a1"), Some(&dec!(100)));
+    }
+
+    #[test]
+    fn inc_counter_from_cumulative_increase_bumps_counter_by_delta() {
+        let counter = counter();
+        let mut last_totals = HashMap::new();
+        last_totals.insert("a1".to_string(), dec!(100));
+
+        inc_counter_from_cumulative(&counter, &["a1"], &mut last_totals, "a1", dec!(150));
+
+        assert_eq!(counter.with_label_values(&["a1"]).get(), 50.0);
+        assert_eq!(last_totals.get("a1"), Some(&dec!(150)));
+    }
+
+    #[test]
+    fn inc_counter_from_cumulative_backwards_total_resets_baseline_without_incrementing() {
+        let counter = counter();
+        let mut last_totals = HashMap::new();
+        last_totals.insert("a1".to_string(), dec!(100));
+
+        inc_counter_from_cumulative(&counter, &["a1"], &mut last_totals, "a1", dec!(40));
+
+        assert_eq!(counter.with_label_values(&["a1"]).get(), 0.0);
+        assert_eq!(last_totals.get("a1"), Some(&dec!(40)));
+    }
+}
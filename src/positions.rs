@@ -0,0 +1,102 @@
+use crate::Amount;
+use lazy_static::lazy_static;
+use prometheus::{register_gauge_vec, GaugeVec};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+lazy_static! {
+    static ref POSITION_QUANTITY: GaugeVec = register_gauge_vec!(
+        "wealthsimple_position_quantity",
+        "the quantity of a security held in a position",
+        &["account_id", "security_symbol", "security_name"]
+    )
+    .unwrap();
+    static ref POSITION_MARKET_VALUE: GaugeVec = register_gauge_vec!(
+        "wealthsimple_position_market_value",
+        "the current market value of a position",
+        &["account_id", "security_symbol", "security_name"]
+    )
+    .unwrap();
+    static ref POSITION_BOOK_VALUE: GaugeVec = register_gauge_vec!(
+        "wealthsimple_position_book_value",
+        "the cost basis of a position",
+        &["account_id", "security_symbol", "security_name"]
+    )
+    .unwrap();
+}
+
+#[derive(Debug, Deserialize)]
+struct Stock<'a> {
+    symbol: &'a str,
+    name: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct Security<'a> {
+    #[serde(borrow)]
+    stock: Stock<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Position<'a> {
+    account_id: &'a str,
+    quantity: Decimal,
+    book_value: Amount,
+    market_value: Amount,
+    #[serde(borrow)]
+    security: Security<'a>,
+}
+
+impl<'a> Position<'a> {
+    /// The `(security_symbol, security_name)` pair that, together with the
+    /// account id, identifies this position's gauge label set.
+    pub(crate) fn key(&self) -> (&'a str, &'a str) {
+        (self.security.stock.symbol, self.security.stock.name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PositionsResponse<'a> {
+    object: &'a str,
+    offset: i64,
+    total_count: i64,
+    #[serde(borrow)]
+    pub(crate) results: Vec<Position<'a>>,
+}
+
+/// Set the per-position gauges for a single holding.
+pub(crate) fn record(position: &Position) {
+    let label_values = &[
+        position.account_id,
+        position.security.stock.symbol,
+        position.security.stock.name,
+    ];
+
+    match position.quantity.to_f64() {
+        Some(value) => POSITION_QUANTITY.with_label_values(label_values).set(value),
+        None => eprintln!(
+            "position quantity {} doesn't fit in an f64, skipping",
+            position.quantity
+        ),
+    }
+
+    crate::set_amount(&POSITION_MARKET_VALUE, label_values, position.market_value.amount);
+    crate::set_amount(&POSITION_BOOK_VALUE, label_values, position.book_value.amount);
+}
+
+/// Remove the gauges for a holding that's no longer in the account (sold
+/// off, or the account itself closed), so a stale quantity/value doesn't
+/// linger in `/metrics` forever.
+pub(crate) fn remove(account_id: &str, security_symbol: &str, security_name: &str) {
+    let label_values = &[account_id, security_symbol, security_name];
+
+    for gauge in [&*POSITION_QUANTITY, &*POSITION_MARKET_VALUE, &*POSITION_BOOK_VALUE] {
+        if let Err(e) = gauge.remove_label_values(label_values) {
+            eprintln!(
+                "failed to remove stale position gauge for {}/{}: {}",
+                account_id, security_symbol, e
+            );
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce};
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Everything needed to resume talking to the Wealthsimple API without
+/// re-running the interactive login/2FA flow.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub device_id: String,
+    pub access_token: String,
+    pub otp_claim: Option<String>,
+}
+
+fn derive_key(password: &Secret<String>, salt: &[u8]) -> Result<[u8; 32], Box<dyn std::error::Error>> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(password.expose_secret().as_bytes(), salt, &mut key)
+        .map_err(|e| format!("failed to derive session key: {}", e))?;
+    Ok(key)
+}
+
+/// Encrypt `session` with a key derived from `password` and write it to
+/// `path`, replacing any file that's already there.
+pub fn save(
+    path: &Path,
+    password: &Secret<String>,
+    session: &Session,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let plaintext = serde_json::to_vec(session)?;
+
+    let mut salt = [0u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let key = derive_key(password, &salt)?;
+
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce_bytes);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let cipher = ChaCha20Poly1305::new_from_slice(&key)?;
+    let ciphertext = cipher
+        .encrypt(nonce, plaintext.as_ref())
+        .map_err(|e| format!("failed to encrypt session: {}", e))?;
+
+    let mut blob = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+    blob.extend_from_slice(&salt);
+    blob.extend_from_slice(&nonce_bytes);
+    blob.extend_from_slice(&ciphertext);
+
+    std::fs::write(path, blob)?;
+    Ok(())
+}
+
+/// Decrypt the session stored at `path` using a key derived from `password`.
+pub fn load(path: &Path, password: &Secret<String>) -> Result<Session, Box<dyn std::error::Error>> {
+    let blob = std::fs::read(path)?;
+    if blob.len() < SALT_LEN + NONCE_LEN {
+        return Err("session file is truncated".into());
+    }
+
+    let (salt, rest) = blob.split_at(SALT_LEN);
+    let (nonce_bytes, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let key = derive_key(password, salt)?;
+    let cipher = ChaCha20Poly1305::new_from_slice(&key)?;
+    let nonce = Nonce::from_slice(nonce_bytes);
+
+    let plaintext = cipher
+        .decrypt(nonce, ciphertext)
+        .map_err(|_| "failed to decrypt session, wrong password?")?;
+
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "ws-session-test-{}-{}.dat",
+            std::process::id(),
+            std::thread::current().name().unwrap_or("main").replace("::", "-")
+        ))
+    }
+
+    #[test]
+    fn save_then_load_round_trips_the_session() {
+        let path = session_path();
+        let password = Secret::new("correct horse battery staple".to_string());
+        let session = Session {
+            device_id: "device-1".to_string(),
+            access_token: "token-1".to_string(),
+            otp_claim: Some("claim-1".to_string()),
+        };
+
+        save(&path, &password, &session).unwrap();
+        let loaded = load(&path, &password).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.device_id, session.device_id);
+        assert_eq!(loaded.access_token, session.access_token);
+        assert_eq!(loaded.otp_claim, session.otp_claim);
+    }
+
+    #[test]
+    fn load_with_the_wrong_password_is_rejected() {
+        let path = session_path();
+        let session = Session {
+            device_id: "device-1".to_string(),
+            access_token: "token-1".to_string(),
+            otp_claim: None,
+        };
+
+        save(&path, &Secret::new("correct password".to_string()), &session).unwrap();
+        let result = load(&path, &Secret::new("wrong password".to_string()));
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+}